@@ -1,5 +1,16 @@
+use core::cell::RefCell;
+
 use super::error::*;
 use board::sdmmc::Sdmmc;
+use board::dma2::Dma2;
+use embedded_sdmmc::{Block, BlockCount, BlockDevice, BlockIdx};
+
+/// Size of an SD card data block in bytes. Transfers are always a multiple of this.
+pub const BLOCK_SIZE: u32 = 512;
+/// `log2(BLOCK_SIZE)`, as programmed into the `DBLOCKSIZE` field of `DCTRL`.
+const BLOCK_SIZE_LOG2: u8 = 9;
+/// 32-bit words in a single 512-byte block, i.e. the DMA transfer count per block.
+const WORDS_PER_BLOCK: usize = (BLOCK_SIZE / 4) as usize;
 
 // Initialization commands
 /// Set the SD card into idle state
@@ -45,7 +56,7 @@ pub fn oper_cond(sdmmc: &mut Sdmmc) -> Result<(), Error> {
 }
 
 /// Get the Card Indentification Number (CID) of the card. (CMD2)
-pub fn send_cid(sdmmc: &mut Sdmmc) -> Result<(), Error> {
+pub fn send_cid(sdmmc: &mut Sdmmc) -> Result<[u32; 4], Error> {
     send_cmd(sdmmc, 0, 2, true, false, 0x03);
 
     get_cmd_resp2(sdmmc, 5000)
@@ -59,18 +70,258 @@ pub fn set_rel_add(sdmmc: &mut Sdmmc) -> Result<u16, Error> {
     get_cmd_resp6(sdmmc, 3, 5000)
 }
 
-pub fn send_csd(sdmmc: &mut Sdmmc, rca: u32) -> Result<(), Error> {
+pub fn send_csd(sdmmc: &mut Sdmmc, rca: u32) -> Result<[u32; 4], Error> {
     send_cmd(sdmmc, rca, 9, true, false, 0x03);
 
     get_cmd_resp2(sdmmc, 5000)
 }
 
+/// Decoded Card Identification (CID) and Card-Specific Data (CSD) of a card.
+///
+/// Populated from the two R2 (CMD2/CMD9) responses; `block_count` gives the valid
+/// LBA range so callers don't issue addresses that trigger `ADDRESS_OUT_OF_RANGE`.
+pub struct CardInfo {
+    /// `CSD_STRUCTURE`: 0 for CSD version 1.0 (SDSC), 1 for version 2.0 (SDHC/SDXC).
+    pub csd_structure: u8,
+    /// Manufacturer ID (`MID`) from the CID.
+    pub manufacturer_id: u8,
+    /// OEM/application ID (`OID`) from the CID.
+    pub oem_id: u16,
+    /// Product name (`PNM`), five ASCII bytes.
+    pub product_name: [u8; 5],
+    /// Product serial number (`PSN`) from the CID.
+    pub serial_number: u32,
+    /// Total capacity in bytes.
+    pub capacity: u64,
+    /// Number of addressable 512-byte blocks.
+    pub block_count: u32,
+    /// Maximum transfer rate (`TRAN_SPEED`) in Hz.
+    pub max_transfer_rate: u32,
+}
+
+impl CardInfo {
+    /// Decode the raw CID (CMD2) and CSD (CMD9) responses into a `CardInfo`.
+    pub fn decode(cid: [u32; 4], csd: [u32; 4]) -> CardInfo {
+        let csd_structure = ((csd[0] & 0xC000_0000) >> 30) as u8;
+
+        let block_count = if csd_structure == 0 {
+            // CSD version 1.0: capacity = (C_SIZE + 1) * 2^(C_SIZE_MULT + 2) * 2^READ_BL_LEN.
+            let read_bl_len = ((csd[1] & 0x000F_0000) >> 16) as u32;
+            let c_size = ((csd[1] & 0x0000_03FF) << 2) | ((csd[2] & 0xC000_0000) >> 30);
+            let c_size_mult = (csd[2] & 0x0003_8000) >> 15;
+            let mult = 1u32 << (c_size_mult + 2);
+            let block_len = 1u32 << read_bl_len;
+            (c_size + 1) * mult * (block_len / BLOCK_SIZE)
+        } else {
+            // CSD version 2.0: capacity = (C_SIZE + 1) * 512 KiB.
+            let c_size = ((csd[1] & 0x0000_003F) << 16) | ((csd[2] & 0xFFFF_0000) >> 16);
+            (c_size + 1) * 1024
+        };
+
+        let manufacturer_id = ((cid[0] & 0xFF00_0000) >> 24) as u8;
+        let oem_id = ((cid[0] & 0x00FF_FF00) >> 8) as u16;
+        let product_name = [
+            (cid[0] & 0x0000_00FF) as u8,
+            ((cid[1] & 0xFF00_0000) >> 24) as u8,
+            ((cid[1] & 0x00FF_0000) >> 16) as u8,
+            ((cid[1] & 0x0000_FF00) >> 8) as u8,
+            (cid[1] & 0x0000_00FF) as u8,
+        ];
+        let serial_number = ((cid[2] & 0x00FF_FFFF) << 8) | ((cid[3] & 0xFF00_0000) >> 24);
+
+        CardInfo {
+            csd_structure,
+            manufacturer_id,
+            oem_id,
+            product_name,
+            serial_number,
+            capacity: block_count as u64 * BLOCK_SIZE as u64,
+            block_count,
+            max_transfer_rate: decode_tran_speed((csd[0] & 0x0000_00FF) as u8),
+        }
+    }
+
+    /// Number of addressable 512-byte blocks; the valid LBA range is `0..block_count()`.
+    pub fn block_count(&self) -> u32 {
+        self.block_count
+    }
+}
+
+/// Decode the `TRAN_SPEED` byte of the CSD into a transfer rate in Hz.
+fn decode_tran_speed(tran_speed: u8) -> u32 {
+    // Bits [2:0] select the unit (100 kbit/s .. 100 Mbit/s), bits [6:3] the value.
+    let unit = match tran_speed & 0x07 {
+        0 => 100_000,
+        1 => 1_000_000,
+        2 => 10_000_000,
+        _ => 100_000_000,
+    };
+    let factor = match (tran_speed & 0x78) >> 3 {
+        1 => 10,
+        2 => 12,
+        3 => 13,
+        4 => 15,
+        5 => 20,
+        6 => 25,
+        7 => 30,
+        8 => 35,
+        9 => 40,
+        10 => 45,
+        11 => 50,
+        12 => 55,
+        13 => 60,
+        14 => 70,
+        15 => 80,
+        _ => 0,
+    };
+    unit * factor / 10
+}
+
 pub fn sel_desel(sdmmc: &mut Sdmmc, rca: u32) -> Result<(), Error> {
     send_cmd(sdmmc, rca, 7, true, false, 0x01);
 
     get_cmd_resp1(sdmmc, 7, 5000)
 }
 
+// Bus-width and speed negotiation
+/// Read the SD Configuration Register (SCR) via ACMD51 and return it as a 64-bit
+/// value (MSB first). Always issues CMD55 first, as ACMD51 is an app command.
+pub fn send_scr(sdmmc: &mut Sdmmc, rca: u32) -> Result<u64, Error> {
+    app(sdmmc, rca)?;
+
+    // The SCR is an 8-byte (two word) block read over the data path.
+    configure_block_read(sdmmc, 8, 3);
+    send_cmd(sdmmc, 0, 51, true, false, 0x01);
+    get_cmd_resp1(sdmmc, 51, 5000)?;
+
+    let mut scr = [0u32; 2];
+    read_fifo(sdmmc, &mut scr, 5000)?;
+
+    // The SD bus transmits MSB first, so each FIFO word arrives byte-reversed
+    // relative to the register's big-endian layout; swap before decoding fields.
+    Ok((u64::from(scr[0].swap_bytes()) << 32) | u64::from(scr[1].swap_bytes()))
+}
+
+/// Negotiate a 4-bit bus if the card's SCR advertises support for it. Issues
+/// ACMD6 with argument `0b10` and widens the controller's `WIDBUS` field.
+/// Returns whether the 4-bit bus was enabled.
+pub fn set_bus_width(sdmmc: &mut Sdmmc, rca: u32) -> Result<bool, Error> {
+    let scr = send_scr(sdmmc, rca)?;
+
+    // SD_BUS_WIDTHS lives in bits [51:48]; bit 50 marks 4-bit support.
+    let bus_widths = (scr >> 48) & 0xF;
+    if bus_widths & 0b0100 == 0 {
+        return Ok(false);
+    }
+
+    app(sdmmc, rca)?;
+    send_cmd(sdmmc, 0b10, 6, true, false, 0x01);
+    get_cmd_resp1(sdmmc, 6, 5000)?;
+
+    sdmmc.clkcr.update(|r| r.set_widbus(0b01));
+
+    Ok(true)
+}
+
+/// Query and, if available, switch the card into High-Speed mode via CMD6
+/// (SWITCH_FUNC) and raise the SDMMC clock. Returns whether High-Speed was
+/// selected.
+pub fn enable_high_speed(sdmmc: &mut Sdmmc, _rca: u32) -> Result<bool, Error> {
+    // "Check" query: does function group 1 offer High-Speed (function 1)?
+    let status = switch_func(sdmmc, 0x00FF_FFF1)?;
+    // Group 1 support bitmap is bits [415:400], i.e. the high half-word of word 3.
+    let group1_support = (status[3] >> 16) & 0xFFFF;
+    if group1_support & 0b10 == 0 {
+        return Ok(false);
+    }
+
+    // "Set": select High-Speed in function group 1.
+    switch_func(sdmmc, 0x80FF_FFF1)?;
+
+    // High-Speed runs the bus at up to 50 MHz. SDMMC_CK = SDMMCCLK / (CLKDIV + 2),
+    // so even CLKDIV = 0 only reaches ~24 MHz from the 48 MHz SDMMCCLK; enable the
+    // clock-divider bypass to drive SDMMC_CK directly at SDMMCCLK (~48 MHz), which
+    // is within the High-Speed ceiling.
+    sdmmc.clkcr.update(|r| {
+        r.set_clkdiv(0);
+        r.set_bypass(true);
+    });
+
+    Ok(true)
+}
+
+/// Issue CMD6 (SWITCH_FUNC) with `argument` and read back the 64-byte status
+/// block the card returns over the data path.
+fn switch_func(sdmmc: &mut Sdmmc, argument: u32) -> Result<[u32; 16], Error> {
+    configure_block_read(sdmmc, 64, 6);
+    send_cmd(sdmmc, argument, 6, true, false, 0x01);
+    get_cmd_resp1(sdmmc, 6, 5000)?;
+
+    let mut status = [0u32; 16];
+    read_fifo(sdmmc, &mut status, 5000)?;
+
+    // Byte-reverse each word, as the status block arrives MSB first off the bus.
+    for word in &mut status {
+        *word = word.swap_bytes();
+    }
+
+    Ok(status)
+}
+
+/// Program the data path for a single `length`-byte block read, where
+/// `block_size_log2` is `log2(length)` as required by `DBLOCKSIZE`.
+fn configure_block_read(sdmmc: &mut Sdmmc, length: u32, block_size_log2: u8) {
+    sdmmc.dtimer.update(|r| r.set_datatime(0xFFFF_FFFF));
+    sdmmc.dlen.update(|r| r.set_datalength(length));
+    sdmmc.dctrl.update(|r| {
+        r.set_dblocksize(block_size_log2);
+        r.set_dtdir(true); // card -> host
+        r.set_dtmode(false);
+        r.set_dmaen(false);
+        r.set_dten(true);
+    });
+}
+
+/// Drain `buf.len()` words from the receive FIFO, polling for data availability
+/// and surfacing CRC/overrun through `check_for_errors` and a data timeout as
+/// `Error::Timeout`.
+fn read_fifo(sdmmc: &mut Sdmmc, buf: &mut [u32], timeout: u32) -> Result<(), Error> {
+    let timeout = ::system_clock::ticks() as u32 + timeout;
+    let mut index = 0;
+    while (::system_clock::ticks() as u32) < timeout
+        && !sdmmc.sta.read().dcrcfail()
+        && !sdmmc.sta.read().dtimeout()
+        && !sdmmc.sta.read().rxoverr() {
+        if sdmmc.sta.read().rxdavl() && index < buf.len() {
+            buf[index] = sdmmc.fifo.read().fifodata();
+            index += 1;
+        }
+        if sdmmc.sta.read().dataend() && index >= buf.len() {
+            break;
+        }
+    }
+
+    if (::system_clock::ticks() as u32) >= timeout {
+        return Err(Error::Timeout);
+    }
+
+    let sta = sdmmc.sta.read();
+    if sta.dtimeout() {
+        clear_all_static_status_flags(sdmmc);
+        return Err(Error::Timeout);
+    }
+    let mut card_status = 0;
+    if sta.dcrcfail() {
+        card_status |= COM_CRC_ERROR.bits();
+    }
+    if sta.rxoverr() {
+        card_status |= CC_ERROR.bits();
+    }
+
+    clear_all_static_status_flags(sdmmc);
+    check_for_errors(card_status)
+}
+
 // Read/Write commands
 /// Set the block length of the blocks to read/write.
 pub fn block_length(sdmmc: &mut Sdmmc, block_size: u32) -> Result<(), Error> {
@@ -86,13 +337,44 @@ pub fn write_single_blk(sdmmc: &mut Sdmmc, block_add: u32) -> Result<(), Error>
     get_cmd_resp1(sdmmc, 24, 5000)
 }
 
-/// Instruct the controller, that multiple blocks will be written. End the write process with a
-/// call to `stop_transfer()`.
-// TODO: This doesn't seem to work...
-pub fn write_multi_blk(sdmmc: &mut Sdmmc, block_add: u32) -> Result<(), Error> {
+/// Write `buf` (a whole number of 512-byte blocks, counted in words) to the card
+/// starting at `block_add` using CMD25 (WRITE_MULTIPLE_BLOCK).
+///
+/// When the card's SCR reports CMD23 support (`cmd23_supported`), the block count
+/// is pre-counted with CMD23 issued immediately before CMD25, so no CMD12 is
+/// needed; otherwise the open-ended CMD25 + `stop_transfer` path is used. Either
+/// way the data-path length is driven from the block count so the controller
+/// raises `DATAEND` at the transfer boundary.
+///
+/// For a transmit the write command is sent *before* the data path is armed, so
+/// the card is already in the receive state when the DPSM starts clocking data
+/// out (the opposite ordering to the read path, which arms before CMD17/CMD18).
+pub fn write_multi_blk(sdmmc: &mut Sdmmc,
+                       dma: &mut Dma2,
+                       rca: u32,
+                       block_add: u32,
+                       buf: &mut [u32],
+                       cmd23_supported: bool) -> Result<(), Error> {
+    let mut transfer = DataTransfer::new(buf, Direction::Transmit)?;
+    let blocks = transfer.block_count();
+
+    if cmd23_supported {
+        set_blk_count(sdmmc, blocks as u16)?;
+    }
+
     send_cmd(sdmmc, block_add, 25, true, false, 0x01);
+    get_cmd_resp1(sdmmc, 25, 5000)?;
+
+    transfer.arm(sdmmc, dma);
+    transfer.finish(sdmmc, dma, 5000)?;
 
-    get_cmd_resp1(sdmmc, 25, 5000)
+    if !cmd23_supported {
+        stop_transfer(sdmmc)?;
+    }
+
+    // The card stays busy committing the last block; don't return until it is
+    // back in the `tran` state so the caller can safely issue the next command.
+    wait_ready(sdmmc, rca, 5000)
 }
 
 /// Instruct the controller, that a single block will be read.
@@ -102,23 +384,148 @@ pub fn read_single_blk(sdmmc: &mut Sdmmc, block_add: u32) -> Result<(), Error> {
     get_cmd_resp1(sdmmc, 17, 5000)
 }
 
-/// Instruct the controller, that multiple blocks will be read. End the read process with a
-/// call to `stop_transfer()`.
-// TODO: This doesn't seem to work...
-pub fn read_multi_blk(sdmmc: &mut Sdmmc, block_add: u32) -> Result<(), Error> {
+/// Read a whole number of 512-byte blocks into `buf` (counted in words) from the
+/// card starting at `block_add` using CMD18 (READ_MULTIPLE_BLOCK).
+///
+/// Mirrors [`write_multi_blk`]: CMD23 pre-counts the transfer when the card
+/// supports it, otherwise the open-ended CMD18 is terminated with `stop_transfer`.
+pub fn read_multi_blk(sdmmc: &mut Sdmmc,
+                      dma: &mut Dma2,
+                      block_add: u32,
+                      buf: &mut [u32],
+                      cmd23_supported: bool) -> Result<(), Error> {
+    let mut transfer = DataTransfer::new(buf, Direction::Receive)?;
+    let blocks = transfer.block_count();
+
+    transfer.arm(sdmmc, dma);
+
+    if cmd23_supported {
+        set_blk_count(sdmmc, blocks as u16)?;
+    }
+
     send_cmd(sdmmc, block_add, 18, true, false, 0x01);
+    get_cmd_resp1(sdmmc, 18, 5000)?;
+
+    transfer.finish(sdmmc, dma, 5000)?;
+
+    if !cmd23_supported {
+        stop_transfer(sdmmc)?;
+    }
+
+    Ok(())
+}
+
+/// Pre-count the blocks of the following CMD18/CMD25 with CMD23 (SET_BLOCK_COUNT).
+///
+/// This only works when issued immediately before the data command on an
+/// otherwise idle command line; the multi-block helpers sequence it that way.
+pub fn set_blk_count(sdmmc: &mut Sdmmc, number_of_blks: u16) -> Result<(), Error> {
+    send_cmd(sdmmc, number_of_blks as u32, 23, true, false, 0x01);
+
+    get_cmd_resp1(sdmmc, 23, 5000)
+}
+
+/// Erase the inclusive range of blocks `[start_block, end_block]`.
+///
+/// Implements the SD erase sequence: CMD32 (ERASE_WR_BLK_START), CMD33
+/// (ERASE_WR_BLK_END) and CMD38 (ERASE). Because the card stays busy in the
+/// programming state for the duration of the erase, this then polls CMD13
+/// (SEND_STATUS) until the card leaves the `prg` state or the timeout expires.
+pub fn erase(sdmmc: &mut Sdmmc, rca: u32, start_block: u32, end_block: u32) -> Result<(), Error> {
+    send_cmd(sdmmc, start_block, 32, true, false, 0x01);
+    get_cmd_resp1(sdmmc, 32, 5000)?;
+
+    send_cmd(sdmmc, end_block, 33, true, false, 0x01);
+    get_cmd_resp1(sdmmc, 33, 5000)?;
+
+    send_cmd(sdmmc, 0, 38, true, false, 0x01);
+    get_cmd_resp1(sdmmc, 38, 5000)?;
+
+    // An erase can take a long time; wait for the card to finish committing.
+    let timeout = ::system_clock::ticks() as u32 + 30000;
+    while (::system_clock::ticks() as u32) < timeout {
+        if card_status(sdmmc, rca)? != CardState::Programming {
+            return Ok(());
+        }
+    }
+
+    Err(Error::Timeout)
+}
+
+/// The `CURRENT_STATE` field of the R1 card status (CMD13).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CardState {
+    /// `idle` – the card is idle.
+    Idle,
+    /// `ready` – the card is ready after reset.
+    Ready,
+    /// `ident` – the card is in the identification state.
+    Identification,
+    /// `stby` – the card is selected but standing by.
+    StandBy,
+    /// `tran` – the card is ready to accept a data command.
+    Transfer,
+    /// `data` – the card is sending data.
+    SendingData,
+    /// `rcv` – the card is receiving data.
+    ReceiveData,
+    /// `prg` – the card is programming (busy).
+    Programming,
+    /// `dis` – the card is disconnected during programming.
+    Disconnected,
+    /// A reserved or otherwise unrecognized state value.
+    Unknown,
+}
+
+impl CardState {
+    fn from_status(card_status: u32) -> CardState {
+        // CURRENT_STATE is bits [12:9] of the R1 response.
+        match (card_status >> 9) & 0xF {
+            0 => CardState::Idle,
+            1 => CardState::Ready,
+            2 => CardState::Identification,
+            3 => CardState::StandBy,
+            4 => CardState::Transfer,
+            5 => CardState::SendingData,
+            6 => CardState::ReceiveData,
+            7 => CardState::Programming,
+            8 => CardState::Disconnected,
+            _ => CardState::Unknown,
+        }
+    }
+}
+
+/// Issue CMD13 (SEND_STATUS) and return the decoded `CURRENT_STATE` of the card.
+pub fn card_status(sdmmc: &mut Sdmmc, rca: u32) -> Result<CardState, Error> {
+    Ok(CardState::from_status(send_status(sdmmc, rca)?))
+}
+
+/// Poll CMD13 (SEND_STATUS) until the card returns to the `tran` state, i.e. it
+/// has finished any internal programming and is ready for the next command.
+pub fn wait_ready(sdmmc: &mut Sdmmc, rca: u32, timeout: u32) -> Result<(), Error> {
+    let timeout = ::system_clock::ticks() as u32 + timeout;
+    while (::system_clock::ticks() as u32) < timeout {
+        if card_status(sdmmc, rca)? == CardState::Transfer {
+            return Ok(());
+        }
+    }
 
-    get_cmd_resp1(sdmmc, 18, 5000)
+    Err(Error::Timeout)
 }
 
-// An alternative, to end multi-block read/write with `stop_transfer()`, is to specify the number of
-// blocks that should be written beforehand.
-// The controller doesn't seem to accept this command and always returns with a CmdRespTimeout Error.
-// pub fn set_blk_count(sdmmc: &mut Sdmmc, number_of_blks: u16) -> Result<(), Error> {
-//     send_cmd(sdmmc, number_of_blks as u32, 23, true, false, 0x01);
-//
-//     get_cmd_resp1(sdmmc, 23, 5000)
-// }
+/// Issue CMD13 (SEND_STATUS) and return the raw R1 card status, checking it
+/// through `check_for_errors`.
+fn send_status(sdmmc: &mut Sdmmc, rca: u32) -> Result<u32, Error> {
+    send_cmd(sdmmc, rca, 13, true, false, 0x01);
+
+    wait_resp_crc(sdmmc, 5000)?;
+    clear_all_static_status_flags(sdmmc);
+
+    let card_status = sdmmc.resp1.read().cardstatus1();
+    check_for_errors(card_status)?;
+
+    Ok(card_status)
+}
 
 /// Stops the tranfer to the card after a multi-block read/write.
 pub fn stop_transfer(sdmmc: &mut Sdmmc) -> Result<(), Error> {
@@ -129,6 +536,232 @@ pub fn stop_transfer(sdmmc: &mut Sdmmc) -> Result<(), Error> {
     Ok(())
 }
 
+// Data path / DMA transfers
+/// Direction of a data-path transfer as seen from the host.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Card to host (a read).
+    Receive,
+    /// Host to card (a write).
+    Transmit,
+}
+
+/// Configures the SDMMC data path and drives a DMA2-backed block transfer.
+///
+/// Unlike the zynq-rs SDIO driver's linked-list ADMA, the STM32F7 DMA2 has no
+/// scatter/gather hardware: a stream moves one contiguous memory region to or
+/// from the SDMMC FIFO given a base pointer and transfer count. This programs
+/// `DTIMER`/`DLEN`/`DCTRL` for the direction and block size, points the DMA2
+/// stream at the caller's buffer, and blocks on `DBCKEND` and the DMA2
+/// transfer-complete flag while surfacing `DCRCFAIL`/`RXOVERR`/`TXUNDERR` through `check_for_errors`.
+pub struct DataTransfer<'a> {
+    buf: &'a mut [u32],
+    direction: Direction,
+}
+
+impl<'a> DataTransfer<'a> {
+    /// Build a transfer over `buf` (counted in 32-bit words, so `512 * blocks / 4`
+    /// long) in the given `direction`. Returns `BlockLenError` if the buffer is
+    /// not a whole number of blocks.
+    pub fn new(buf: &'a mut [u32], direction: Direction) -> Result<Self, Error> {
+        if buf.is_empty() || buf.len() % WORDS_PER_BLOCK != 0 {
+            return Err(Error::CardError { t: BLOCK_LEN_ERROR });
+        }
+
+        Ok(DataTransfer { buf, direction })
+    }
+
+    /// Number of 512-byte blocks this transfer covers.
+    pub fn block_count(&self) -> u32 {
+        (self.buf.len() / WORDS_PER_BLOCK) as u32
+    }
+
+    /// Program the data path and kick off the DMA2 transfer, then block until the
+    /// card signals `DBCKEND` and the DMA2 stream completes, or an error occurs.
+    ///
+    /// Convenience for single transfers where no command has to be interleaved;
+    /// multi-block reads arm the data path first with [`arm`](Self::arm), issue
+    /// the card command, then block with [`finish`](Self::finish), while
+    /// multi-block writes issue the card command before calling `arm`.
+    pub fn run(&mut self, sdmmc: &mut Sdmmc, dma: &mut Dma2, timeout: u32) -> Result<(), Error> {
+        self.arm(sdmmc, dma);
+        self.finish(sdmmc, dma, timeout)
+    }
+
+    /// Configure `DTIMER`/`DLEN`/`DCTRL` and start the DMA2 stream on the caller's
+    /// buffer. For a receive this is called *before* the read command so the
+    /// data-path state machine is ready when the card starts streaming; for a
+    /// transmit it is called *after* the write command's response.
+    pub fn arm(&mut self, sdmmc: &mut Sdmmc, dma: &mut Dma2) {
+        let length = self.block_count() * BLOCK_SIZE;
+
+        // Data timeout (in card clock cycles) and total transfer length in bytes.
+        sdmmc.dtimer.update(|r| r.set_datatime(0xFFFF_FFFF));
+        sdmmc.dlen.update(|r| r.set_datalength(length));
+
+        let receiving = self.direction == Direction::Receive;
+        sdmmc.dctrl.update(|r| {
+            r.set_dblocksize(BLOCK_SIZE_LOG2);
+            r.set_dtdir(receiving);
+            r.set_dtmode(false); // block data transfer
+            r.set_dmaen(true);
+            r.set_dten(true);
+        });
+
+        // Clear any stale stream event flags from a previous block so this
+        // transfer's completion is observed cleanly; `BlockDevice::read` re-arms
+        // the stream once per block in a loop.
+        clear_dma_flags(dma);
+
+        // Point the DMA2 stream at the buffer and enable it. The peripheral side
+        // is the SDMMC FIFO (a fixed address preconfigured in `par`); the memory
+        // side is a single contiguous run of `buf.len()` words.
+        dma.m0ar.update(|r| r.set_m0a(self.buf.as_ptr() as u32));
+        dma.ndtr.update(|r| r.set_ndt(self.buf.len() as u16));
+        dma.cr.update(|r| {
+            // 0b00 peripheral-to-memory (read), 0b01 memory-to-peripheral (write).
+            r.set_dir(if receiving { 0b00 } else { 0b01 });
+            r.set_en(true);
+        });
+    }
+
+    /// Block on the data-path flags, then disable the DMA2 stream and the data path.
+    pub fn finish(&mut self, sdmmc: &mut Sdmmc, dma: &mut Dma2, timeout: u32) -> Result<(), Error> {
+        let result = self.wait(sdmmc, dma, timeout);
+
+        dma.cr.update(|r| r.set_en(false));
+        sdmmc.dctrl.update(|r| {
+            r.set_dten(false);
+            r.set_dmaen(false);
+        });
+
+        result
+    }
+
+    /// Block on the data-path status flags, mapping a CRC/overrun/underrun into a
+    /// card error via `check_for_errors`.
+    fn wait(&mut self, sdmmc: &mut Sdmmc, dma: &mut Dma2, timeout: u32) -> Result<(), Error> {
+        let timeout = ::system_clock::ticks() as u32 + timeout;
+        // `DATAEND` (DCOUNT == 0) can fire while DMA is still draining the final
+        // FIFO words into the buffer, so wait for the block to fully complete
+        // (`DBCKEND`, raised after the CRC check) *and* the DMA stream's
+        // transfer-complete flag before letting `finish` tear the stream down.
+        while (::system_clock::ticks() as u32) < timeout
+            && !(sdmmc.sta.read().dbckend() && dma.isr.read().tcif())
+            && !sdmmc.sta.read().dcrcfail()
+            && !sdmmc.sta.read().dtimeout()
+            && !sdmmc.sta.read().rxoverr()
+            && !sdmmc.sta.read().txunderr() {}
+
+        if (::system_clock::ticks() as u32) >= timeout {
+            return Err(Error::Timeout);
+        }
+
+        // Translate the data-path error flags into the shared card-status bits so
+        // they flow through the same `check_for_errors` path as command errors.
+        let sta = sdmmc.sta.read();
+        let mut card_status = 0;
+        if sta.dcrcfail() {
+            card_status |= COM_CRC_ERROR.bits();
+        }
+        if sta.rxoverr() || sta.txunderr() {
+            card_status |= CC_ERROR.bits();
+        }
+
+        clear_all_static_status_flags(sdmmc);
+        check_for_errors(card_status)
+    }
+}
+
+/// Clear the DMA2 stream's event flags (transfer-complete, half-transfer, and the
+/// error flags) so a freshly armed transfer reports its own completion.
+fn clear_dma_flags(dma: &mut Dma2) {
+    dma.ifcr.update(|r| {
+        r.set_ctcif(true);
+        r.set_chtif(true);
+        r.set_cteif(true);
+        r.set_cdmeif(true);
+        r.set_cfeif(true);
+    });
+}
+
+/// Adapter exposing an [`Sdmmc`] as an embedded-sdmmc [`BlockDevice`], so a card
+/// can be handed straight to the crate's FAT16/FAT32 volume manager.
+///
+/// The `BlockDevice` trait takes `&self`, while the command helpers need
+/// `&mut Sdmmc`; the peripheral and its DMA channel are therefore held behind a
+/// `RefCell`, matching how shared-but-mutable peripherals are threaded elsewhere.
+pub struct SdmmcBlockDevice<'a> {
+    sdmmc: RefCell<&'a mut Sdmmc>,
+    dma: RefCell<&'a mut Dma2>,
+    rca: u32,
+    block_count: u32,
+}
+
+impl<'a> SdmmcBlockDevice<'a> {
+    /// Wrap an initialized card. `rca` is the card's relative address (from
+    /// [`set_rel_add`]) already shifted into bits [31:16], as it is sent unchanged
+    /// as the CMD13 argument; `block_count` comes from [`CardInfo::block_count`].
+    pub fn new(sdmmc: &'a mut Sdmmc, dma: &'a mut Dma2, rca: u32, block_count: u32) -> Self {
+        SdmmcBlockDevice {
+            sdmmc: RefCell::new(sdmmc),
+            dma: RefCell::new(dma),
+            rca,
+            block_count,
+        }
+    }
+}
+
+impl<'a> BlockDevice for SdmmcBlockDevice<'a> {
+    type Error = Error;
+
+    fn read(&self, blocks: &mut [Block], start: BlockIdx, _reason: &str) -> Result<(), Error> {
+        let mut sdmmc = self.sdmmc.borrow_mut();
+        let mut dma = self.dma.borrow_mut();
+
+        for (i, block) in blocks.iter_mut().enumerate() {
+            let mut words = [0u32; WORDS_PER_BLOCK];
+            let mut transfer = DataTransfer::new(&mut words, Direction::Receive)?;
+
+            // Arm the receiver before CMD17 so the data path is ready the instant
+            // the card starts streaming after its R1 response.
+            transfer.arm(*sdmmc, *dma);
+            read_single_blk(*sdmmc, start.0 + i as u32)?;
+            transfer.finish(*sdmmc, *dma, 5000)?;
+
+            for (word, chunk) in words.iter().zip(block.contents.chunks_mut(4)) {
+                chunk.copy_from_slice(&word.to_le_bytes());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, blocks: &[Block], start: BlockIdx) -> Result<(), Error> {
+        let mut sdmmc = self.sdmmc.borrow_mut();
+        let mut dma = self.dma.borrow_mut();
+
+        for (i, block) in blocks.iter().enumerate() {
+            let mut words = [0u32; WORDS_PER_BLOCK];
+            for (word, chunk) in words.iter_mut().zip(block.contents.chunks(4)) {
+                *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+            }
+
+            write_single_blk(*sdmmc, start.0 + i as u32)?;
+            DataTransfer::new(&mut words, Direction::Transmit)?.run(*sdmmc, *dma, 5000)?;
+
+            // Wait out the card's programming state before the next block.
+            wait_ready(*sdmmc, self.rca, 5000)?;
+        }
+
+        Ok(())
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, Error> {
+        Ok(BlockCount(self.block_count))
+    }
+}
+
 /// Send a command to the card.
 pub fn send_cmd(sdmmc: &mut Sdmmc,
                 argument: u32, cmdidx: u8,
@@ -164,12 +797,20 @@ fn get_cmd_resp1(sdmmc: &mut Sdmmc, cmd_idx: u8, timeout: u32) -> Result<(), Err
     Ok(())
 }
 
-fn get_cmd_resp2(sdmmc: &mut Sdmmc, timeout: u32) -> Result<(), Error> {
+fn get_cmd_resp2(sdmmc: &mut Sdmmc, timeout: u32) -> Result<[u32; 4], Error> {
     wait_resp_crc(sdmmc, timeout)?;
 
+    // The R2 response holds the 128-bit CID/CSD across all four response registers.
+    let resp = [
+        sdmmc.resp1.read().cardstatus1(),
+        sdmmc.resp2.read().cardstatus2(),
+        sdmmc.resp3.read().cardstatus3(),
+        sdmmc.resp4.read().cardstatus4(),
+    ];
+
     clear_all_static_status_flags(sdmmc);
 
-    Ok(())
+    Ok(resp)
 }
 
 fn get_cmd_resp3(sdmmc: &mut Sdmmc, timeout: u32) -> Result<(), Error> {
@@ -294,3 +935,64 @@ fn check_for_errors(card_status: u32) -> Result<(), Error> {
         Err(Error::CardError { t: ERROR })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_csd_v2_capacity_and_block_count() {
+        // CSD v2: structure = 1, TRAN_SPEED = 0x32 (25 MHz), C_SIZE = 999.
+        let csd = [0x4000_0032, 0x0000_0000, 0x03E7_0000, 0x0000_0000];
+        let cid = [0x0000_0000, 0x0000_0000, 0x0000_0000, 0x0000_0000];
+
+        let info = CardInfo::decode(cid, csd);
+
+        assert_eq!(info.csd_structure, 1);
+        // (C_SIZE + 1) * 1024 blocks.
+        assert_eq!(info.block_count(), 1_024_000);
+        assert_eq!(info.capacity, 1_024_000 * 512);
+        assert_eq!(info.max_transfer_rate, 25_000_000);
+    }
+
+    #[test]
+    fn decode_csd_v1_capacity_and_block_count() {
+        // CSD v1: READ_BL_LEN = 9 (512 byte), C_SIZE = 3 (its low two bits sit in
+        // the top of csd[2]), C_SIZE_MULT = 0.
+        // block_count = (C_SIZE + 1) * 2^(C_SIZE_MULT + 2) * (2^READ_BL_LEN / 512).
+        let csd = [0x0000_0000, 0x0009_0000, 0xC000_0000, 0x0000_0000];
+        let cid = [0; 4];
+
+        let info = CardInfo::decode(cid, csd);
+
+        assert_eq!(info.csd_structure, 0);
+        assert_eq!(info.block_count(), (3 + 1) * 4);
+    }
+
+    #[test]
+    fn decode_cid_identity_fields() {
+        let cid = [0x0353_4453, 0x4443_4152, 0x0012_3456, 0x7800_0000];
+        let csd = [0x4000_0000, 0x0000_0000, 0x0000_0000, 0x0000_0000];
+
+        let info = CardInfo::decode(cid, csd);
+
+        assert_eq!(info.manufacturer_id, 0x03);
+        assert_eq!(info.oem_id, 0x5344);
+        assert_eq!(&info.product_name, b"SDCAR");
+        assert_eq!(info.serial_number, 0x1234_5678);
+    }
+
+    #[test]
+    fn tran_speed_decodes_standard_rates() {
+        assert_eq!(decode_tran_speed(0x32), 25_000_000);
+        assert_eq!(decode_tran_speed(0x5A), 50_000_000);
+    }
+
+    #[test]
+    fn card_state_maps_current_state_field() {
+        assert_eq!(CardState::from_status(0 << 9), CardState::Idle);
+        assert_eq!(CardState::from_status(4 << 9), CardState::Transfer);
+        assert_eq!(CardState::from_status(7 << 9), CardState::Programming);
+        assert_eq!(CardState::from_status(0xF << 9), CardState::Unknown);
+    }
+}